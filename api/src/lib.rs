@@ -1,9 +1,22 @@
+use bytes::Bytes;
 use chrono::Utc;
+use lru::LruCache;
 use reqwest::header;
 use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, OnceLock};
 use std::time::Duration;
 use tokio::{sync::mpsc, time::sleep};
 
+const ARTWORK_CACHE_CAPACITY: usize = 64;
+
+type ArtworkCache = Mutex<LruCache<String, (Bytes, Option<String>)>>;
+
+fn artwork_cache() -> &'static ArtworkCache {
+    static CACHE: OnceLock<ArtworkCache> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(LruCache::new(NonZeroUsize::new(ARTWORK_CACHE_CAPACITY).unwrap())))
+}
+
 macro_rules! string_enum {
     (pub enum $name:ident {
         $($variant:ident = { name: $value:literal, url: $url:literal },)*
@@ -99,6 +112,10 @@ pub struct Country {
 pub struct SearchResults {
     pub albums: Vec<Album>,
     pub tracks: Vec<Track>,
+    #[serde(default)]
+    pub playlists: Vec<Album>,
+    #[serde(default)]
+    pub artists: Vec<Artist>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -145,6 +162,7 @@ impl Track {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Artist {
+    pub url: Option<String>,
     pub name: String,
 }
 
@@ -161,6 +179,75 @@ pub struct DownloadResponse {
     pub content_type: Option<String>,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum LucidaError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+
+    #[error("{0}")]
+    Api(String),
+}
+
+trait ApiSuccess {
+    fn success(&self) -> bool;
+    fn error_message(&self) -> Option<String> {
+        None
+    }
+}
+
+impl ApiSuccess for StreamResponse {
+    fn success(&self) -> bool {
+        self.success
+    }
+
+    fn error_message(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+impl ApiSuccess for SearchResponse {
+    fn success(&self) -> bool {
+        self.success
+    }
+}
+
+impl ApiSuccess for CountriesResponse {
+    fn success(&self) -> bool {
+        self.success
+    }
+}
+
+impl ApiSuccess for MetadataResponse {
+    fn success(&self) -> bool {
+        self.success
+    }
+}
+
+impl ApiSuccess for StatusResponse {
+    fn success(&self) -> bool {
+        self.success
+    }
+
+    fn error_message(&self) -> Option<String> {
+        Some(self.message.clone())
+    }
+}
+
+async fn send_and_parse<T>(request: reqwest::RequestBuilder) -> Result<T, LucidaError>
+where
+    T: serde::de::DeserializeOwned + ApiSuccess,
+{
+    let value: T = request.send().await?.json().await?;
+    if !value.success() {
+        return Err(LucidaError::Api(
+            value
+                .error_message()
+                .unwrap_or_else(|| "request failed".to_string()),
+        ));
+    }
+    Ok(value)
+}
+
 string_enum! {
     pub enum LucidaService {
         Qobuz = { name: "qobuz", url: "qobuz.com" },
@@ -172,10 +259,19 @@ string_enum! {
     }
 }
 
+impl LucidaService {
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::try_from(value).ok()
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum TryDownloadAllCountriesError {
     #[error(transparent)]
-    RequestError(#[from] reqwest::Error),
+    Api(#[from] LucidaError),
+
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
 
     #[error("unknown service")]
     UnknownService,
@@ -184,6 +280,36 @@ pub enum TryDownloadAllCountriesError {
     NoAvailableCountries,
 }
 
+#[derive(Clone, Copy)]
+pub enum LucidaHost {
+    LucidaTo,
+    LucidaSu,
+}
+
+impl LucidaHost {
+    fn domain(self) -> &'static str {
+        match self {
+            LucidaHost::LucidaTo => "lucida.to",
+            LucidaHost::LucidaSu => "lucida.su",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum LucidaServer {
+    Hund,
+    Katze,
+}
+
+impl LucidaServer {
+    fn subdomain(self) -> &'static str {
+        match self {
+            LucidaServer::Hund => "hund",
+            LucidaServer::Katze => "katze",
+        }
+    }
+}
+
 pub struct LucidaClient {
     client: reqwest::Client,
     base_url: String,
@@ -211,10 +337,15 @@ impl LucidaClient {
         self
     }
 
+    pub fn with_options(host: LucidaHost, server: LucidaServer) -> Self {
+        Self::new().with_base_url(format!("https://{}.{}", server.subdomain(), host.domain()))
+    }
+
     pub async fn try_download_all_countries(
         &self,
         url: &str,
         metadata: bool,
+        quality: &str,
         tx: mpsc::Sender<String>,
     ) -> Result<DownloadResponse, TryDownloadAllCountriesError> {
         let Ok(service) = LucidaService::try_from(url) else {
@@ -223,14 +354,22 @@ impl LucidaClient {
         let countries = self.fetch_countries(service).await?;
         for country in countries.countries {
             for _ in 0..3 {
-                let stream_response = self
-                    .fetch_stream(url, Some(&country.code), metadata)
-                    .await?;
-                if !stream_response.success {
-                    continue;
-                }
+                let stream_response = match self
+                    .fetch_stream(url, Some(&country.code), metadata, quality)
+                    .await
+                {
+                    Ok(stream_response) => stream_response,
+                    Err(e) => {
+                        tx.send(format!("Stream request failed: {e}"))
+                            .await
+                            .ok();
+                        continue;
+                    }
+                };
 
-                let id = stream_response.handoff.unwrap();
+                let Some(id) = stream_response.handoff else {
+                    continue;
+                };
 
                 let mut status_response = self.fetch_status(&id).await?;
                 let mut status_message = status_response.message;
@@ -277,37 +416,36 @@ impl LucidaClient {
         url: &str,
         country: Option<&str>,
         metadata: bool,
-    ) -> Result<StreamResponse, reqwest::Error> {
-        self.client
-            .post(format!("{}/api/fetch/stream/v2", self.base_url))
-            .json(&StreamRequest {
-                account: Account {
-                    id: country.unwrap_or("auto").to_string(),
-                    account_type: "country".to_string(),
-                },
-                downscale: "original".to_string(),
-                handoff: true,
-                metadata,
-                private: true,
-                upload: Upload {
-                    enabled: false,
-                    service: "pixeldrain".to_string(),
-                },
-                url: url.to_string(),
-            })
-            .send()
-            .await?
-            .json()
-            .await
+        quality: &str,
+    ) -> Result<StreamResponse, LucidaError> {
+        send_and_parse(
+            self.client
+                .post(format!("{}/api/fetch/stream/v2", self.base_url))
+                .json(&StreamRequest {
+                    account: Account {
+                        id: country.unwrap_or("auto").to_string(),
+                        account_type: "country".to_string(),
+                    },
+                    downscale: quality.to_string(),
+                    handoff: true,
+                    metadata,
+                    private: true,
+                    upload: Upload {
+                        enabled: false,
+                        service: "pixeldrain".to_string(),
+                    },
+                    url: url.to_string(),
+                }),
+        )
+        .await
     }
 
-    pub async fn fetch_status(&self, id: &str) -> Result<StatusResponse, reqwest::Error> {
-        self.client
-            .get(format!("{}/api/fetch/request/{id}", self.base_url))
-            .send()
-            .await?
-            .json()
-            .await
+    pub async fn fetch_status(&self, id: &str) -> Result<StatusResponse, LucidaError> {
+        send_and_parse(
+            self.client
+                .get(format!("{}/api/fetch/request/{id}", self.base_url)),
+        )
+        .await
     }
 
     pub async fn fetch_download(&self, id: &str) -> Result<DownloadResponse, reqwest::Error> {
@@ -341,40 +479,52 @@ impl LucidaClient {
         service: LucidaService,
         country: &str,
         query: &str,
-    ) -> Result<SearchResponse, reqwest::Error> {
-        self.client
-            .get(format!("{}/api/search", self.base_url))
-            .query(&[
-                ("query", query),
-                ("service", service.into()),
-                ("country", country),
-            ])
-            .send()
-            .await?
-            .json()
-            .await
+    ) -> Result<SearchResponse, LucidaError> {
+        send_and_parse(self.client.get(format!("{}/api/search", self.base_url)).query(&[
+            ("query", query),
+            ("service", service.into()),
+            ("country", country),
+        ]))
+        .await
     }
 
     pub async fn fetch_countries(
         &self,
         service: LucidaService,
-    ) -> Result<CountriesResponse, reqwest::Error> {
-        self.client
-            .get(format!("{}/api/countries", self.base_url))
-            .query(&[("service", Into::<&str>::into(service))])
-            .send()
-            .await?
-            .json()
-            .await
+    ) -> Result<CountriesResponse, LucidaError> {
+        send_and_parse(
+            self.client
+                .get(format!("{}/api/countries", self.base_url))
+                .query(&[("service", Into::<&str>::into(service))]),
+        )
+        .await
     }
 
-    pub async fn fetch_metadata(&self, url: &str) -> Result<MetadataResponse, reqwest::Error> {
-        self.client
-            .get(format!("{}/api/fetch/metadata", self.base_url))
-            .query(&[("url", url)])
-            .send()
-            .await?
-            .json()
-            .await
+    pub async fn fetch_metadata(&self, url: &str) -> Result<MetadataResponse, LucidaError> {
+        send_and_parse(
+            self.client
+                .get(format!("{}/api/fetch/metadata", self.base_url))
+                .query(&[("url", url)]),
+        )
+        .await
+    }
+
+    pub async fn fetch_artwork(&self, url: &str) -> Result<(Bytes, Option<String>), reqwest::Error> {
+        if let Some(cached) = artwork_cache().lock().unwrap().get(url) {
+            return Ok(cached.clone());
+        }
+
+        let response = self.client.get(url).send().await?;
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let bytes = response.bytes().await?;
+        artwork_cache()
+            .lock()
+            .unwrap()
+            .put(url.to_string(), (bytes.clone(), content_type.clone()));
+        Ok((bytes, content_type))
     }
 }
@@ -1,8 +1,14 @@
 use clap::{Parser, Subcommand};
 use console::style;
 use dialoguer::{Select, theme::ColorfulTheme};
-use lucida_api::{LucidaClient, LucidaHost, LucidaServer, LucidaService, SearchResponse};
-use tokio::{fs, sync::mpsc};
+use futures::stream::{FuturesUnordered, StreamExt};
+use lucida_api::{LucidaClient, LucidaHost, LucidaServer, LucidaService, SearchResponse, Track};
+use queue::Queue;
+use std::sync::{Arc, Mutex};
+use tokio::{fs, sync::Semaphore, sync::mpsc};
+
+mod queue;
+mod tag;
 
 #[derive(Parser)]
 struct Cli {
@@ -25,6 +31,50 @@ struct Cli {
 
     #[arg(long, help = "Embed metadata", global = true)]
     metadata: bool,
+
+    #[arg(
+        long,
+        help = "Tag the downloaded file with title/artist/album/cover art",
+        global = true
+    )]
+    tag: bool,
+
+    #[arg(
+        long,
+        help = "Save the cover art next to the audio file as cover.jpg",
+        global = true
+    )]
+    save_cover: bool,
+
+    #[arg(
+        long,
+        help = "Maximum number of tracks to download in parallel",
+        global = true,
+        default_value_t = 1
+    )]
+    concurrency: usize,
+
+    #[arg(
+        long,
+        help = "Stream quality: original, lossless, high, low (default: original)",
+        global = true,
+        default_value = "original"
+    )]
+    quality: String,
+
+    #[arg(
+        long,
+        help = "Transcode the downloaded file to this format (e.g. mp3, opus) via ffmpeg",
+        global = true
+    )]
+    convert: Option<String>,
+
+    #[arg(
+        long,
+        help = "Persist download progress in a SQLite database to resume crashed batch jobs",
+        global = true
+    )]
+    queue: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -33,6 +83,14 @@ enum Commands {
         #[arg(short, long, help = "Streaming service track link")]
         url: String,
     },
+    Playlist {
+        #[arg(short, long, help = "Streaming service playlist link")]
+        url: String,
+    },
+    Artist {
+        #[arg(short, long, help = "Streaming service artist link")]
+        url: String,
+    },
     Search {
         #[arg(short, long, help = "Search query")]
         query: String,
@@ -48,6 +106,28 @@ enum Commands {
         #[arg(short, long, help = "Search query")]
         query: String,
 
+        #[arg(
+            short,
+            long,
+            help = "Streaming service: qobuz, tidal, soundcloud, deezer, amazon, yandex"
+        )]
+        service: String,
+    },
+    SearchPlaylist {
+        #[arg(short, long, help = "Search query")]
+        query: String,
+
+        #[arg(
+            short,
+            long,
+            help = "Streaming service: qobuz, tidal, soundcloud, deezer, amazon, yandex"
+        )]
+        service: String,
+    },
+    SearchArtist {
+        #[arg(short, long, help = "Search query")]
+        query: String,
+
         #[arg(
             short,
             long,
@@ -61,23 +141,112 @@ async fn search(
     client: &LucidaClient,
     service: &str,
     query: &str,
-) -> Result<SearchResponse, &'static str> {
-    let Some(service) = LucidaService::from_str(service) else {
-        return Err("Unknown service");
+) -> Result<SearchResponse, String> {
+    let Some(service) = LucidaService::parse(service) else {
+        return Err("Unknown service".to_string());
     };
-    let countries = client.fetch_countries(service.clone()).await.unwrap();
+    let countries = client
+        .fetch_countries(service.clone())
+        .await
+        .map_err(|e| e.to_string())?;
     if countries.countries.len() == 0 {
-        return Err("Service unavailable");
+        return Err("Service unavailable".to_string());
     }
     let country = countries.countries[0].code.clone();
-    Ok(client.fetch_search(service, &country, query).await.unwrap())
+    client
+        .fetch_search(service, &country, query)
+        .await
+        .map_err(|e| e.to_string())
 }
 
-async fn download_and_save(
+fn log_error(e: impl std::fmt::Display) {
+    eprintln!("{} {}", style("Error:").bold().red(), e);
+}
+
+async fn tag_track(
     client: &LucidaClient,
+    path: &str,
+    track: &Track,
+    current: usize,
+) -> Result<(), tag::TagError> {
+    let cover = match track.artwork() {
+        Some(url) => client
+            .fetch_artwork(&url)
+            .await
+            .ok()
+            .map(|(bytes, content_type)| (bytes.to_vec(), content_type)),
+        None => None,
+    };
+    tag::tag_file(path, track, current, cover)
+}
+
+async fn save_cover(client: &LucidaClient, track: &Track, filename: &str) {
+    let Some(url) = track.artwork() else {
+        return;
+    };
+    let Ok((bytes, _)) = client.fetch_artwork(&url).await else {
+        return;
+    };
+    let cover_path = std::path::Path::new(filename)
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new("."))
+        .join("cover.jpg");
+    let _ = fs::write(cover_path, bytes).await;
+}
+
+async fn convert_file(filename: &str, format: &str) -> std::io::Result<String> {
+    let stem = std::path::Path::new(filename)
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    let output = format!("{stem}.{format}");
+
+    if output == filename {
+        return Ok(output);
+    }
+
+    let mut command = tokio::process::Command::new("ffmpeg");
+    command.args(["-y", "-i", filename]);
+    match format {
+        "mp3" => {
+            command.args(["-codec:a", "libmp3lame", "-qscale:a", "0"]);
+        }
+        "opus" => {
+            command.args(["-codec:a", "libopus"]);
+        }
+        _ => {}
+    }
+    command.arg(&output);
+
+    let result = command.output().await?;
+    if !result.status.success() {
+        return Err(std::io::Error::other(format!(
+            "ffmpeg exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    fs::remove_file(filename).await?;
+    Ok(output)
+}
+
+#[derive(Clone)]
+struct DownloadOptions {
+    metadata: bool,
+    quality: String,
+    tag: bool,
+    save_cover_art: bool,
+    convert: Option<String>,
+    queue: Option<Arc<Mutex<Queue>>>,
+}
+
+async fn download_and_save(
+    client: Arc<LucidaClient>,
     url: &str,
     title: &str,
-    metadata: bool,
+    track: Option<&Track>,
+    options: &DownloadOptions,
     current: usize,
     total: usize,
     append_current: bool,
@@ -91,6 +260,14 @@ async fn download_and_save(
             style(m).bold()
         )
     };
+
+    if let Some(queue) = &options.queue {
+        if queue.lock().unwrap().is_completed(url).unwrap_or(false) {
+            log("Already downloaded, skipping");
+            return;
+        }
+    }
+
     loop {
         let title = title.to_string();
         let (tx, mut rx) = mpsc::channel::<String>(16);
@@ -99,7 +276,10 @@ async fn download_and_save(
                 log(&message.replace("{item}", &title));
             }
         });
-        let response = match client.try_download_all_countries(url, metadata, tx).await {
+        let response = match client
+            .try_download_all_countries(url, options.metadata, &options.quality, tx)
+            .await
+        {
             Ok(response) => response,
             Err(e) => {
                 log(&format!("Download failed, retrying... ({e})"));
@@ -107,18 +287,80 @@ async fn download_and_save(
             }
         };
 
-        let mut filename = response.filename.unwrap().replace("/", "&");
+        let Some(filename) = response.filename else {
+            log("Download failed: server did not return a filename, retrying...");
+            continue;
+        };
+        let mut filename = filename.replace("/", "&");
         if append_current {
             filename = format!("{current}. {filename}");
         }
 
-        fs::write(filename, response.response.bytes().await.unwrap())
-            .await
-            .unwrap();
+        if let Some(queue) = &options.queue {
+            queue.lock().unwrap().enqueue(url, &filename).ok();
+        }
+
+        let bytes = match response.response.bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                log(&format!("Download failed: {e}, retrying..."));
+                continue;
+            }
+        };
+        if let Err(e) = fs::write(&filename, bytes).await {
+            log(&format!("Failed to write file: {e}, retrying..."));
+            continue;
+        }
+
+        if let Some(queue) = &options.queue {
+            queue.lock().unwrap().mark_completed(url).ok();
+        }
+
+        if let Some(format) = &options.convert {
+            match convert_file(&filename, format).await {
+                Ok(converted) => filename = converted,
+                Err(e) => log(&format!("Failed to convert file: {e}")),
+            }
+        }
+
+        if let Some(track) = track {
+            if options.tag {
+                if let Err(e) = tag_track(&client, &filename, track, current).await {
+                    log(&format!("Failed to tag file: {e}"));
+                }
+            }
+            if options.save_cover_art {
+                save_cover(&client, track, &filename).await;
+            }
+        }
         break;
     }
 }
 
+async fn download_tracks(
+    client: &Arc<LucidaClient>,
+    tracks: &[Track],
+    options: &DownloadOptions,
+    concurrency: usize,
+) {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let total = tracks.len();
+    let mut tasks = FuturesUnordered::new();
+    for (i, track) in tracks.iter().enumerate() {
+        let client = client.clone();
+        let semaphore = semaphore.clone();
+        let track = track.clone();
+        let options = options.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore.acquire().await.unwrap();
+            let url = track.url.clone();
+            let title = track.title.clone();
+            download_and_save(client, &url, &title, Some(&track), &options, i + 1, total, true).await;
+        }));
+    }
+    while tasks.next().await.is_some() {}
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
@@ -139,14 +381,46 @@ async fn main() {
     } else {
         LucidaServer::Hund
     };
-    let client = LucidaClient::with_options(host, server);
+    let client = Arc::new(LucidaClient::with_options(host, server));
+    let queue = match cli.queue.as_ref() {
+        Some(path) => match Queue::open(path) {
+            Ok(queue) => Some(Arc::new(Mutex::new(queue))),
+            Err(e) => return log_error(format!("Failed to open queue database: {e}")),
+        },
+        None => None,
+    };
+    let options = DownloadOptions {
+        metadata: cli.metadata,
+        quality: cli.quality.clone(),
+        tag: cli.tag,
+        save_cover_art: cli.save_cover,
+        convert: cli.convert.clone(),
+        queue,
+    };
 
     match &cli.command {
         Commands::Rip { url } => {
-            download_and_save(&client, &url, &url, cli.metadata, 1, 1, false).await
+            download_and_save(client.clone(), &url, &url, None, &options, 1, 1, false).await
+        }
+        Commands::Playlist { url } => {
+            let playlist = match client.fetch_metadata(&url).await {
+                Ok(playlist) => playlist,
+                Err(e) => return log_error(e),
+            };
+            download_tracks(&client, &playlist.tracks, &options, cli.concurrency).await;
+        }
+        Commands::Artist { url } => {
+            let artist = match client.fetch_metadata(&url).await {
+                Ok(artist) => artist,
+                Err(e) => return log_error(e),
+            };
+            download_tracks(&client, &artist.tracks, &options, cli.concurrency).await;
         }
         Commands::Search { query, service } => {
-            let response = search(&client, &service, &query).await.unwrap();
+            let response = match search(&client, &service, &query).await {
+                Ok(response) => response,
+                Err(e) => return log_error(e),
+            };
             let selector: Vec<String> = response
                 .results
                 .tracks
@@ -168,10 +442,11 @@ async fn main() {
                 .interact()
                 .unwrap();
             download_and_save(
-                &client,
+                client.clone(),
                 &response.results.tracks[selection].url,
                 &selector[selection],
-                cli.metadata,
+                Some(&response.results.tracks[selection]),
+                &options,
                 1,
                 1,
                 false,
@@ -179,7 +454,10 @@ async fn main() {
             .await;
         }
         Commands::SearchAlbum { query, service } => {
-            let response = search(&client, &service, &query).await.unwrap();
+            let response = match search(&client, &service, &query).await {
+                Ok(response) => response,
+                Err(e) => return log_error(e),
+            };
             let selector: Vec<String> = response
                 .results
                 .albums
@@ -189,7 +467,13 @@ async fn main() {
                         "{} {} {}",
                         style(&v.title).bold(),
                         style("-").dim(),
-                        style(&v.artists.as_ref().unwrap()[0].name).dim()
+                        style(
+                            v.artists
+                                .as_ref()
+                                .and_then(|a| a.first())
+                                .map_or("Unknown", |a| &a.name)
+                        )
+                        .dim()
                     )
                 })
                 .collect();
@@ -200,22 +484,81 @@ async fn main() {
                 .items(&selector)
                 .interact()
                 .unwrap();
-            let album = client
+            let album = match client
                 .fetch_metadata(&response.results.albums[selection].url)
                 .await
+            {
+                Ok(album) => album,
+                Err(e) => return log_error(e),
+            };
+            download_tracks(&client, &album.tracks, &options, cli.concurrency).await;
+        }
+        Commands::SearchPlaylist { query, service } => {
+            let response = match search(&client, &service, &query).await {
+                Ok(response) => response,
+                Err(e) => return log_error(e),
+            };
+            let selector: Vec<String> = response
+                .results
+                .playlists
+                .iter()
+                .map(|v| {
+                    format!(
+                        "{} {} {}",
+                        style(&v.title).bold(),
+                        style("-").dim(),
+                        style(
+                            v.artists
+                                .as_ref()
+                                .and_then(|a| a.first())
+                                .map_or("Unknown", |a| &a.name)
+                        )
+                        .dim()
+                    )
+                })
+                .collect();
+            let selection = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Choose the playlist")
+                .default(0)
+                .max_length(5)
+                .items(&selector)
+                .interact()
                 .unwrap();
-            for i in 0..album.tracks.len() {
-                download_and_save(
-                    &client,
-                    &album.tracks[i].url,
-                    &album.tracks[i].title,
-                    cli.metadata,
-                    i + 1,
-                    album.tracks.len(),
-                    true,
-                )
-                .await;
-            }
+            let playlist = match client
+                .fetch_metadata(&response.results.playlists[selection].url)
+                .await
+            {
+                Ok(playlist) => playlist,
+                Err(e) => return log_error(e),
+            };
+            download_tracks(&client, &playlist.tracks, &options, cli.concurrency).await;
+        }
+        Commands::SearchArtist { query, service } => {
+            let response = match search(&client, &service, &query).await {
+                Ok(response) => response,
+                Err(e) => return log_error(e),
+            };
+            let selector: Vec<String> = response
+                .results
+                .artists
+                .iter()
+                .map(|v| style(&v.name).bold().to_string())
+                .collect();
+            let selection = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Choose the artist")
+                .default(0)
+                .max_length(5)
+                .items(&selector)
+                .interact()
+                .unwrap();
+            let Some(artist_url) = response.results.artists[selection].url.as_ref() else {
+                return log_error("Selected artist has no URL");
+            };
+            let artist = match client.fetch_metadata(artist_url).await {
+                Ok(artist) => artist,
+                Err(e) => return log_error(e),
+            };
+            download_tracks(&client, &artist.tracks, &options, cli.concurrency).await;
         }
     }
 }
@@ -0,0 +1,80 @@
+use rusqlite::{Connection, OptionalExtension};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackStatus {
+    Pending,
+    Completed,
+}
+
+impl TrackStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            TrackStatus::Pending => "pending",
+            TrackStatus::Completed => "completed",
+        }
+    }
+}
+
+pub struct Queue {
+    conn: Connection,
+}
+
+impl Queue {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS tracks (
+                url TEXT PRIMARY KEY,
+                filename TEXT NOT NULL,
+                status TEXT NOT NULL
+            )",
+            (),
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn is_completed(&self, url: &str) -> rusqlite::Result<bool> {
+        let status: Option<String> = self
+            .conn
+            .query_row("SELECT status FROM tracks WHERE url = ?1", [url], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(status.as_deref() == Some(TrackStatus::Completed.as_str()))
+    }
+
+    pub fn enqueue(&self, url: &str, filename: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO tracks (url, filename, status) VALUES (?1, ?2, ?3)",
+            (url, filename, TrackStatus::Pending.as_str()),
+        )?;
+        Ok(())
+    }
+
+    pub fn mark_completed(&self, url: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE tracks SET status = ?1 WHERE url = ?2",
+            (TrackStatus::Completed.as_str(), url),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enqueue_then_mark_completed_round_trips() {
+        let queue = Queue::open(":memory:").unwrap();
+        assert!(!queue.is_completed("https://example.com/track").unwrap());
+
+        queue
+            .enqueue("https://example.com/track", "01. Track.flac")
+            .unwrap();
+        assert!(!queue.is_completed("https://example.com/track").unwrap());
+
+        queue.mark_completed("https://example.com/track").unwrap();
+        assert!(queue.is_completed("https://example.com/track").unwrap());
+    }
+}
@@ -0,0 +1,70 @@
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::probe::Probe;
+use lofty::tag::{Accessor, ItemKey};
+use lucida_api::Track;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TagError {
+    #[error(transparent)]
+    Lofty(#[from] lofty::error::LoftyError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+fn mime_type_for(content_type: Option<&str>) -> MimeType {
+    match content_type {
+        Some("image/png") => MimeType::Png,
+        Some("image/gif") => MimeType::Gif,
+        Some("image/bmp") => MimeType::Bmp,
+        Some("image/tiff") => MimeType::Tiff,
+        _ => MimeType::Jpeg,
+    }
+}
+
+pub fn tag_file(
+    path: &str,
+    track: &Track,
+    current: usize,
+    cover: Option<(Vec<u8>, Option<String>)>,
+) -> Result<(), TagError> {
+    let mut tagged_file = Probe::open(path)?.read()?;
+
+    let tag = match tagged_file.primary_tag_mut() {
+        Some(tag) => tag,
+        None => {
+            let tag_type = tagged_file.primary_tag_type();
+            tagged_file.insert_tag(lofty::tag::Tag::new(tag_type));
+            tagged_file.primary_tag_mut().unwrap()
+        }
+    };
+
+    tag.set_title(track.title.clone());
+    tag.set_artist(
+        track
+            .artists
+            .iter()
+            .map(|a| a.name.clone())
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    if let Some(album) = &track.album {
+        tag.set_album(album.title.clone());
+    }
+    tag.set_track(current as u32);
+    tag.insert_text(ItemKey::Length, track.duration_ms.to_string());
+
+    if let Some((cover, content_type)) = cover {
+        tag.push_picture(Picture::new_unchecked(
+            PictureType::CoverFront,
+            Some(mime_type_for(content_type.as_deref())),
+            None,
+            cover,
+        ));
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}